@@ -0,0 +1,40 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use engine_traits::Result;
+use kvproto::kvrpcpb::ApiVersion;
+
+use crate::{APIVersion, KeyMode, RawValue, UserValue, APIV1};
+
+impl APIVersion for APIV1 {
+    const TAG: ApiVersion = ApiVersion::V1;
+    const IS_TTL_ENABLED: bool = false;
+
+    fn parse_key_mode(_key: &[u8]) -> KeyMode {
+        KeyMode::Unknown
+    }
+
+    fn parse_range_mode(_range: (Option<&[u8]>, Option<&[u8]>)) -> KeyMode {
+        KeyMode::Unknown
+    }
+
+    fn decode_raw_value(bytes: &[u8]) -> Result<RawValue<&[u8]>> {
+        Ok(RawValue {
+            user_value: UserValue::Inline(bytes),
+            expire_ts: None,
+            checksum: None,
+            write_time: None,
+            codec: crate::CODEC_NONE,
+        })
+    }
+
+    fn encode_raw_value(value: RawValue<&[u8]>) -> Vec<u8> {
+        value.user_value.as_ref().to_vec()
+    }
+
+    fn encode_raw_value_owned(value: RawValue<Vec<u8>>) -> Vec<u8> {
+        match value.user_value {
+            UserValue::Inline(v) => v,
+            UserValue::Indirect(digest) => digest.to_vec(),
+        }
+    }
+}
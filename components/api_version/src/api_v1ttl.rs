@@ -0,0 +1,62 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use engine_traits::Result;
+use kvproto::kvrpcpb::ApiVersion;
+use tikv_util::{
+    box_err,
+    codec::number::{self, NumberEncoder},
+};
+
+use crate::{APIVersion, KeyMode, RawValue, UserValue, APIV1TTL};
+
+/// The length, in bytes, of the expire_ts suffix appended to every V1TTL value.
+const EXPIRE_TS_LEN: usize = number::U64_SIZE;
+
+impl APIVersion for APIV1TTL {
+    const TAG: ApiVersion = ApiVersion::V1ttl;
+    const IS_TTL_ENABLED: bool = true;
+
+    fn parse_key_mode(_key: &[u8]) -> KeyMode {
+        KeyMode::Raw
+    }
+
+    fn parse_range_mode(_range: (Option<&[u8]>, Option<&[u8]>)) -> KeyMode {
+        KeyMode::Raw
+    }
+
+    fn decode_raw_value(bytes: &[u8]) -> Result<RawValue<&[u8]>> {
+        if bytes.len() < EXPIRE_TS_LEN {
+            return Err(box_err!(
+                "invalid api v1ttl value, expire_ts len {}",
+                bytes.len()
+            ));
+        }
+        let pos = bytes.len() - EXPIRE_TS_LEN;
+        let mut expire_ts_slice = &bytes[pos..];
+        let expire_ts = number::decode_u64(&mut expire_ts_slice)?;
+        let expire_ts = if expire_ts == 0 { None } else { Some(expire_ts) };
+        Ok(RawValue {
+            user_value: UserValue::Inline(&bytes[..pos]),
+            expire_ts,
+            checksum: None,
+            write_time: None,
+            codec: crate::CODEC_NONE,
+        })
+    }
+
+    fn encode_raw_value(value: RawValue<&[u8]>) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(value.user_value.as_ref().len() + EXPIRE_TS_LEN);
+        encoded.extend_from_slice(value.user_value.as_ref());
+        encoded.encode_u64(value.expire_ts.unwrap_or(0)).unwrap();
+        encoded
+    }
+
+    fn encode_raw_value_owned(value: RawValue<Vec<u8>>) -> Vec<u8> {
+        let mut user_value = match value.user_value {
+            UserValue::Inline(v) => v,
+            UserValue::Indirect(digest) => digest.to_vec(),
+        };
+        user_value.encode_u64(value.expire_ts.unwrap_or(0)).unwrap();
+        user_value
+    }
+}
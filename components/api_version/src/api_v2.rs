@@ -0,0 +1,335 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::io::{Read, Write};
+
+use engine_traits::Result;
+use kvproto::kvrpcpb::ApiVersion;
+use tikv_util::{
+    box_err,
+    codec::number::{self, NumberEncoder},
+};
+use txn_types::{Key, TimeStamp};
+
+use crate::{
+    APIVersion, KeyMode, RawValue, UserValue, APIV2, CODEC_LZ4, CODEC_NONE, CODEC_ZSTD, DIGEST_LEN,
+};
+
+pub const RAW_KEY_PREFIX: u8 = b'r';
+pub const TXN_KEY_PREFIX: u8 = b'x';
+pub const TIDB_META_KEY_PREFIX: u8 = b'm';
+pub const TIDB_TABLE_KEY_PREFIX: u8 = b't';
+
+/// The length, in bytes, of the expire_ts field carried by a V2 value.
+const EXPIRE_TS_LEN: usize = number::U64_SIZE;
+
+/// Meta flag bit 0: the value carries an 8-byte expire_ts immediately before
+/// the flag byte, or before the codec id when the value is compressed.
+const FLAG_EXPIRE_TS: u8 = 0b0000_0001;
+/// Meta flag bit 1: the slot that would otherwise hold the user value instead
+/// holds a `DIGEST_LEN`-byte content hash of the value, which is stored
+/// out-of-line in a separate blob store.
+const FLAG_INDIRECT: u8 = 0b0000_0010;
+/// Meta flag bit 2: the user value is compressed; a one-byte codec id
+/// precedes the flag byte (after `expire_ts`, if any).
+const FLAG_COMPRESSED: u8 = 0b0000_0100;
+/// All meta flag bits currently understood by this crate.
+const FLAG_ALL: u8 = FLAG_EXPIRE_TS | FLAG_INDIRECT | FLAG_COMPRESSED;
+
+impl APIVersion for APIV2 {
+    const TAG: ApiVersion = ApiVersion::V2;
+    const IS_TTL_ENABLED: bool = true;
+
+    fn parse_key_mode(key: &[u8]) -> KeyMode {
+        if key.is_empty() {
+            return KeyMode::Unknown;
+        }
+        match key[0] {
+            RAW_KEY_PREFIX => KeyMode::Raw,
+            TXN_KEY_PREFIX => KeyMode::Txn,
+            TIDB_META_KEY_PREFIX | TIDB_TABLE_KEY_PREFIX => KeyMode::TiDB,
+            _ => KeyMode::Unknown,
+        }
+    }
+
+    fn parse_range_mode(range: (Option<&[u8]>, Option<&[u8]>)) -> KeyMode {
+        if let (Some(start), Some(end)) = range {
+            if !start.is_empty() && !end.is_empty() {
+                let mode = Self::parse_key_mode(start);
+                if mode != KeyMode::Unknown
+                    && (end[0] == start[0] || (end.len() == 1 && end[0] == start[0] + 1))
+                {
+                    return mode;
+                }
+            }
+        }
+        KeyMode::Unknown
+    }
+
+    /// Decodes `bytes` into its metadata and user value.
+    ///
+    /// When the value is compressed (`FLAG_COMPRESSED`), `user_value` holds
+    /// the still-compressed payload: a borrowed slice can't own a freshly
+    /// inflated buffer. Use [`APIVersion::decode_raw_value_owned`] (which
+    /// `APIV2` overrides) to get the transparently decompressed plaintext, or
+    /// inflate `user_value` yourself using the returned `codec`.
+    fn decode_raw_value(bytes: &[u8]) -> Result<RawValue<&[u8]>> {
+        let &flags = bytes
+            .last()
+            .ok_or_else(|| box_err!("invalid api v2 value: {:?}, flags is missing", bytes))?;
+        if flags & !FLAG_ALL != 0 {
+            return Err(box_err!("undefined flags: {:b}", flags));
+        }
+
+        let mut rest_len = bytes.len() - 1;
+        let codec = if flags & FLAG_COMPRESSED != 0 {
+            if rest_len < 1 {
+                return Err(box_err!("invalid api v2 value, codec id is missing"));
+            }
+            rest_len -= 1;
+            let codec = bytes[rest_len];
+            if !matches!(codec, CODEC_LZ4 | CODEC_ZSTD) {
+                return Err(box_err!("undefined codec id: {}", codec));
+            }
+            codec
+        } else {
+            CODEC_NONE
+        };
+
+        let expire_ts = if flags & FLAG_EXPIRE_TS != 0 {
+            if rest_len < EXPIRE_TS_LEN {
+                return Err(box_err!(
+                    "invalid api v2 value, expire_ts len {}",
+                    rest_len
+                ));
+            }
+            rest_len -= EXPIRE_TS_LEN;
+            let mut expire_ts_slice = &bytes[rest_len..rest_len + EXPIRE_TS_LEN];
+            Some(number::decode_u64(&mut expire_ts_slice)?)
+        } else {
+            None
+        };
+
+        let user_value = if flags & FLAG_INDIRECT != 0 {
+            if rest_len != DIGEST_LEN {
+                return Err(box_err!(
+                    "invalid api v2 value, indirect digest len {}",
+                    rest_len
+                ));
+            }
+            let mut digest = [0u8; DIGEST_LEN];
+            digest.copy_from_slice(&bytes[..rest_len]);
+            UserValue::Indirect(digest)
+        } else {
+            UserValue::Inline(&bytes[..rest_len])
+        };
+
+        Ok(RawValue {
+            user_value,
+            expire_ts,
+            checksum: None,
+            write_time: None,
+            codec,
+        })
+    }
+
+    /// This is equivalent to `encode_raw_value_with_codec(value, CODEC_NONE)`.
+    fn encode_raw_value(value: RawValue<&[u8]>) -> Vec<u8> {
+        encode_raw_value_with_codec(value, CODEC_NONE).unwrap()
+    }
+
+    fn encode_raw_value_owned(value: RawValue<Vec<u8>>) -> Vec<u8> {
+        encode_raw_value_owned_with_codec(value, CODEC_NONE).unwrap()
+    }
+
+    /// Transparently decompresses the user value, in addition to what the
+    /// default implementation does.
+    fn decode_raw_value_owned(bytes: Vec<u8>) -> Result<RawValue<Vec<u8>>> {
+        let (len, expire_ts, is_indirect, codec) = {
+            let raw_value = Self::decode_raw_value(&bytes)?;
+            (
+                raw_value.user_value.as_ref().len(),
+                raw_value.expire_ts,
+                raw_value.user_value.is_indirect(),
+                raw_value.codec,
+            )
+        };
+        let mut payload = bytes;
+        payload.truncate(len);
+
+        let user_value = if is_indirect {
+            // Digests are fixed-size content hashes and are never compressed.
+            let mut digest = [0u8; DIGEST_LEN];
+            digest.copy_from_slice(&payload);
+            UserValue::Indirect(digest)
+        } else {
+            UserValue::Inline(decompress(codec, &payload)?)
+        };
+
+        Ok(RawValue {
+            user_value,
+            expire_ts,
+            checksum: None,
+            write_time: None,
+            codec: CODEC_NONE,
+        })
+    }
+
+    fn decode_raw_key(encoded_key: &Key, with_ts: bool) -> Result<(Vec<u8>, Option<TimeStamp>)> {
+        if with_ts {
+            let ts = encoded_key.decode_ts().unwrap();
+            let user_key = encoded_key.truncate_ts().unwrap().into_raw().unwrap();
+            Ok((user_key, Some(ts)))
+        } else {
+            Ok((encoded_key.to_raw().unwrap(), None))
+        }
+    }
+
+    fn decode_raw_key_owned(
+        encoded_key: Key,
+        with_ts: bool,
+    ) -> Result<(Vec<u8>, Option<TimeStamp>)> {
+        if with_ts {
+            let ts = encoded_key.decode_ts().unwrap();
+            let user_key = encoded_key.truncate_ts().unwrap().into_raw().unwrap();
+            Ok((user_key, Some(ts)))
+        } else {
+            Ok((encoded_key.into_raw().unwrap(), None))
+        }
+    }
+
+    fn encode_raw_key(user_key: &[u8], ts: Option<TimeStamp>) -> Key {
+        let key = Key::from_raw(user_key);
+        match ts {
+            Some(ts) => key.append_ts(ts),
+            None => key,
+        }
+    }
+
+    fn encode_raw_key_owned(user_key: Vec<u8>, ts: Option<TimeStamp>) -> Key {
+        let key = Key::from_raw(&user_key);
+        match ts {
+            Some(ts) => key.append_ts(ts),
+            None => key,
+        }
+    }
+}
+
+/// Encodes `value` like `APIV2::encode_raw_value`, additionally compressing
+/// the user value with `codec` first (pass `CODEC_NONE` to skip
+/// compression). Errors on an undefined codec id.
+///
+/// Exposed as a free function, rather than on the `APIVersion` trait,
+/// because the choice of codec is a per-call decision (e.g. driven by a
+/// size/ratio heuristic) rather than a property of the `ApiVersion` itself.
+pub fn encode_raw_value_with_codec(value: RawValue<&[u8]>, codec: u8) -> Result<Vec<u8>> {
+    if !matches!(codec, CODEC_NONE | CODEC_LZ4 | CODEC_ZSTD) {
+        return Err(box_err!("undefined codec id: {}", codec));
+    }
+    if value.user_value.is_indirect() {
+        // Digests are fixed-size content hashes; compressing them would only
+        // add overhead.
+        let mut flags = FLAG_INDIRECT;
+        let mut encoded = Vec::with_capacity(value.user_value.as_ref().len() + EXPIRE_TS_LEN + 1);
+        encoded.extend_from_slice(value.user_value.as_ref());
+        if let Some(expire_ts) = value.expire_ts {
+            flags |= FLAG_EXPIRE_TS;
+            encoded.encode_u64(expire_ts).unwrap();
+        }
+        encoded.push(flags);
+        return Ok(encoded);
+    }
+    let compressed;
+    let payload: &[u8] = if codec == CODEC_NONE {
+        value.user_value.as_ref()
+    } else {
+        compressed = compress(codec, value.user_value.as_ref())?;
+        &compressed
+    };
+
+    let mut flags = 0;
+    let mut encoded = Vec::with_capacity(payload.len() + EXPIRE_TS_LEN + 2);
+    encoded.extend_from_slice(payload);
+    if let Some(expire_ts) = value.expire_ts {
+        flags |= FLAG_EXPIRE_TS;
+        encoded.encode_u64(expire_ts).unwrap();
+    }
+    if codec != CODEC_NONE {
+        flags |= FLAG_COMPRESSED;
+        encoded.push(codec);
+    }
+    encoded.push(flags);
+    Ok(encoded)
+}
+
+/// This is equivalent to `encode_raw_value_with_codec` but reduces an allocation.
+pub fn encode_raw_value_owned_with_codec(value: RawValue<Vec<u8>>, codec: u8) -> Result<Vec<u8>> {
+    if !matches!(codec, CODEC_NONE | CODEC_LZ4 | CODEC_ZSTD) {
+        return Err(box_err!("undefined codec id: {}", codec));
+    }
+    let is_indirect = value.user_value.is_indirect();
+    let mut user_value = match value.user_value {
+        UserValue::Inline(v) => v,
+        UserValue::Indirect(digest) => digest.to_vec(),
+    };
+    if is_indirect {
+        let mut flags = FLAG_INDIRECT;
+        if let Some(expire_ts) = value.expire_ts {
+            flags |= FLAG_EXPIRE_TS;
+            user_value.encode_u64(expire_ts).unwrap();
+        }
+        user_value.push(flags);
+        return Ok(user_value);
+    }
+
+    if codec != CODEC_NONE {
+        user_value = compress(codec, &user_value)?;
+    }
+    let mut flags = 0;
+    if let Some(expire_ts) = value.expire_ts {
+        flags |= FLAG_EXPIRE_TS;
+        user_value.encode_u64(expire_ts).unwrap();
+    }
+    if codec != CODEC_NONE {
+        flags |= FLAG_COMPRESSED;
+        user_value.push(codec);
+    }
+    user_value.push(flags);
+    Ok(user_value)
+}
+
+fn compress(codec: u8, data: &[u8]) -> Result<Vec<u8>> {
+    match codec {
+        CODEC_LZ4 => {
+            let mut encoder = lz4::EncoderBuilder::new()
+                .build(Vec::new())
+                .map_err(|e| box_err!("lz4 encoder init failed: {}", e))?;
+            encoder
+                .write_all(data)
+                .map_err(|e| box_err!("lz4 compression failed: {}", e))?;
+            let (buf, result) = encoder.finish();
+            result.map_err(|e| box_err!("lz4 compression failed: {}", e))?;
+            Ok(buf)
+        }
+        CODEC_ZSTD => zstd::stream::encode_all(data, 0)
+            .map_err(|e| box_err!("zstd compression failed: {}", e)),
+        _ => Err(box_err!("undefined codec id: {}", codec)),
+    }
+}
+
+fn decompress(codec: u8, data: &[u8]) -> Result<Vec<u8>> {
+    match codec {
+        CODEC_NONE => Ok(data.to_vec()),
+        CODEC_LZ4 => {
+            let mut decoder =
+                lz4::Decoder::new(data).map_err(|e| box_err!("lz4 decoder init failed: {}", e))?;
+            let mut out = Vec::new();
+            decoder
+                .read_to_end(&mut out)
+                .map_err(|e| box_err!("lz4 decompression failed: {}", e))?;
+            Ok(out)
+        }
+        CODEC_ZSTD => zstd::stream::decode_all(data)
+            .map_err(|e| box_err!("zstd decompression failed: {}", e)),
+        _ => Err(box_err!("undefined codec id: {}", codec)),
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A forward-compatible raw value encoding for [`APIV2Ext`].
+//!
+//! Unlike [`crate::api_v2`], which dedicates a single meta-flag byte to
+//! carry per-value metadata, this module lays metadata out as a
+//! self-describing, tag-length-value (TLV) trailer appended after the user
+//! value. New metadata can then be added in the future simply by defining a
+//! new tag, without breaking decoders that don't understand it yet -- they
+//! just skip unrecognised tags.
+//!
+//! ## Layout
+//!
+//! ```text
+//! --------------------------------------------------------------------------------
+//! | User value (or digest) | Record 1 | Record 2 | ... | Trailer len | Format |
+//! --------------------------------------------------------------------------------
+//! ```
+//!
+//! Each record is `(tag: u8, len: varint, bytes)`. `Trailer len` is the
+//! fixed-size (4 byte, big-endian) byte length of the concatenated records,
+//! which lets a decoder locate the start of the trailer by reading backwards
+//! from the end of the value. `Format` is a single format-marker byte,
+//! reserved so that the trailer layout itself can change in the future.
+//!
+//! Tags are always emitted in ascending order for deterministic encoding.
+
+use engine_traits::Result;
+use kvproto::kvrpcpb::ApiVersion;
+use tikv_util::{
+    box_err,
+    codec::number::{self, NumberEncoder},
+};
+
+use crate::{APIVersion, KeyMode, RawValue, UserValue, APIV2, APIV2Ext, DIGEST_LEN};
+
+/// Tag for the expire_ts record: 8 bytes, big-endian unix seconds.
+const TAG_EXPIRE_TS: u8 = 0x01;
+/// Tag for the CRC32 checksum of the user value: 4 bytes, big-endian.
+const TAG_CHECKSUM: u8 = 0x02;
+/// Tag for the writer/creation timestamp: 8 bytes, big-endian unix seconds.
+const TAG_WRITE_TIME: u8 = 0x03;
+/// Tag marking that the user value slot holds a `DIGEST_LEN`-byte content
+/// hash rather than the real payload. Zero-length record.
+const TAG_INDIRECT: u8 = 0x04;
+
+/// The only trailer format understood so far.
+const FORMAT_V1: u8 = 1;
+/// Byte length of the fixed-size trailer-length field.
+const TRAILER_LEN_SIZE: usize = 4;
+/// Byte length of the format marker.
+const FORMAT_MARKER_SIZE: usize = 1;
+/// Byte length of the CRC32 checksum record's value.
+const CHECKSUM_LEN: usize = 4;
+
+impl APIVersion for APIV2Ext {
+    const TAG: ApiVersion = ApiVersion::V2;
+    const IS_TTL_ENABLED: bool = true;
+
+    fn parse_key_mode(key: &[u8]) -> KeyMode {
+        APIV2::parse_key_mode(key)
+    }
+
+    fn parse_range_mode(range: (Option<&[u8]>, Option<&[u8]>)) -> KeyMode {
+        APIV2::parse_range_mode(range)
+    }
+
+    fn decode_raw_value(bytes: &[u8]) -> Result<RawValue<&[u8]>> {
+        if bytes.len() < TRAILER_LEN_SIZE + FORMAT_MARKER_SIZE {
+            return Err(box_err!(
+                "invalid api v2ext value: {:?}, trailer is missing",
+                bytes
+            ));
+        }
+        let (body, format_marker) = bytes.split_at(bytes.len() - FORMAT_MARKER_SIZE);
+        if format_marker[0] != FORMAT_V1 {
+            return Err(box_err!(
+                "unsupported api v2ext trailer format: {}",
+                format_marker[0]
+            ));
+        }
+        let (body, trailer_len_slice) = body.split_at(body.len() - TRAILER_LEN_SIZE);
+        let trailer_len = u32::from_be_bytes(trailer_len_slice.try_into().unwrap()) as usize;
+        if trailer_len > body.len() {
+            return Err(box_err!(
+                "invalid api v2ext value, trailer len {} exceeds value len {}",
+                trailer_len,
+                body.len()
+            ));
+        }
+        let records_start = body.len() - trailer_len;
+        let (user_value, mut records) = body.split_at(records_start);
+
+        let mut expire_ts = None;
+        let mut checksum = None;
+        let mut write_time = None;
+        let mut is_indirect = false;
+        while !records.is_empty() {
+            let tag = records[0];
+            records = &records[1..];
+            let len = number::decode_var_u64(&mut records)? as usize;
+            if len > records.len() {
+                return Err(box_err!(
+                    "invalid api v2ext value, record len {} exceeds remaining trailer {}",
+                    len,
+                    records.len()
+                ));
+            }
+            let (value, rest) = records.split_at(len);
+            match tag {
+                TAG_EXPIRE_TS => {
+                    let mut v = value;
+                    expire_ts = Some(number::decode_u64(&mut v)?);
+                }
+                TAG_CHECKSUM => {
+                    if value.len() != CHECKSUM_LEN {
+                        return Err(box_err!(
+                            "invalid api v2ext value, checksum record len {}",
+                            value.len()
+                        ));
+                    }
+                    checksum = Some(u32::from_be_bytes(value.try_into().unwrap()));
+                }
+                TAG_WRITE_TIME => {
+                    let mut v = value;
+                    write_time = Some(number::decode_u64(&mut v)?);
+                }
+                TAG_INDIRECT => {
+                    is_indirect = true;
+                }
+                // Unknown tags are skipped, not rejected, to stay forward-compatible.
+                _ => {}
+            }
+            records = rest;
+        }
+
+        let user_value = if is_indirect {
+            if user_value.len() != DIGEST_LEN {
+                return Err(box_err!(
+                    "invalid api v2ext value, indirect digest len {}",
+                    user_value.len()
+                ));
+            }
+            let mut digest = [0u8; DIGEST_LEN];
+            digest.copy_from_slice(user_value);
+            UserValue::Indirect(digest)
+        } else {
+            UserValue::Inline(user_value)
+        };
+
+        Ok(RawValue {
+            user_value,
+            expire_ts,
+            checksum,
+            write_time,
+            codec: crate::CODEC_NONE,
+        })
+    }
+
+    fn encode_raw_value(value: RawValue<&[u8]>) -> Vec<u8> {
+        let mut encoded = value.user_value.as_ref().to_vec();
+        let trailer_start = encoded.len();
+        encode_trailer_records(&mut encoded, &value);
+        let trailer_len = (encoded.len() - trailer_start) as u32;
+        encoded.extend_from_slice(&trailer_len.to_be_bytes());
+        encoded.push(FORMAT_V1);
+        encoded
+    }
+
+    fn encode_raw_value_owned(value: RawValue<Vec<u8>>) -> Vec<u8> {
+        let is_indirect = value.user_value.is_indirect();
+        let mut encoded = match value.user_value {
+            UserValue::Inline(v) => v,
+            UserValue::Indirect(digest) => digest.to_vec(),
+        };
+        let trailer_start = encoded.len();
+        encode_trailer_records(
+            &mut encoded,
+            &RawValue {
+                user_value: if is_indirect {
+                    UserValue::Indirect([0u8; DIGEST_LEN])
+                } else {
+                    UserValue::Inline(&[][..])
+                },
+                expire_ts: value.expire_ts,
+                checksum: value.checksum,
+                write_time: value.write_time,
+                codec: crate::CODEC_NONE,
+            },
+        );
+        let trailer_len = (encoded.len() - trailer_start) as u32;
+        encoded.extend_from_slice(&trailer_len.to_be_bytes());
+        encoded.push(FORMAT_V1);
+        encoded
+    }
+}
+
+/// Appends the `(tag, len, bytes)` records, in ascending tag order, for every
+/// metadata field present on `value`. The indirect-value record is driven by
+/// `value.user_value.is_indirect()`; its payload is not re-encoded here
+/// because the digest itself was already written as the leading user-value
+/// bytes by the caller.
+fn encode_trailer_records<T: AsRef<[u8]>>(encoded: &mut Vec<u8>, value: &RawValue<T>) {
+    if let Some(expire_ts) = value.expire_ts {
+        encoded.push(TAG_EXPIRE_TS);
+        encoded.encode_var_u64(number::U64_SIZE as u64).unwrap();
+        encoded.encode_u64(expire_ts).unwrap();
+    }
+    if let Some(checksum) = value.checksum {
+        encoded.push(TAG_CHECKSUM);
+        encoded.encode_var_u64(CHECKSUM_LEN as u64).unwrap();
+        encoded.extend_from_slice(&checksum.to_be_bytes());
+    }
+    if let Some(write_time) = value.write_time {
+        encoded.push(TAG_WRITE_TIME);
+        encoded.encode_var_u64(number::U64_SIZE as u64).unwrap();
+        encoded.encode_u64(write_time).unwrap();
+    }
+    if value.user_value.is_indirect() {
+        encoded.push(TAG_INDIRECT);
+        encoded.encode_var_u64(0).unwrap();
+    }
+}
@@ -5,6 +5,8 @@
 mod api_v1;
 mod api_v1ttl;
 pub mod api_v2;
+pub mod api_v2ext;
+pub mod transcode;
 
 use engine_traits::Result;
 use kvproto::kvrpcpb::ApiVersion;
@@ -24,15 +26,32 @@ pub trait APIVersion: Clone + Copy + 'static + Send + Sync {
     fn decode_raw_value(bytes: &[u8]) -> Result<RawValue<&[u8]>>;
     /// This is equivalent to `decode_raw_value()` but returns the owned user value.
     fn decode_raw_value_owned(mut bytes: Vec<u8>) -> Result<RawValue<Vec<u8>>> {
-        let (len, expire_ts) = {
+        let (len, expire_ts, is_indirect, checksum, write_time, codec) = {
             let raw_value = Self::decode_raw_value(&bytes)?;
-            (raw_value.user_value.len(), raw_value.expire_ts)
+            (
+                raw_value.user_value.as_ref().len(),
+                raw_value.expire_ts,
+                raw_value.user_value.is_indirect(),
+                raw_value.checksum,
+                raw_value.write_time,
+                raw_value.codec,
+            )
         };
-        // The user value are always the first part in encoded bytes.
+        // The user value (or its indirect digest) is always the first part in encoded bytes.
         bytes.truncate(len);
+        let user_value = if is_indirect {
+            let mut digest = [0u8; DIGEST_LEN];
+            digest.copy_from_slice(&bytes);
+            UserValue::Indirect(digest)
+        } else {
+            UserValue::Inline(bytes)
+        };
         Ok(RawValue {
-            user_value: bytes,
+            user_value,
             expire_ts,
+            checksum,
+            write_time,
+            codec,
         })
     }
     /// Encode the raw value and it's metadata into bytes.
@@ -68,6 +87,13 @@ pub struct APIV1;
 pub struct APIV1TTL;
 #[derive(Default, Clone, Copy)]
 pub struct APIV2;
+/// An alternate encoding for `ApiVersion::V2` raw values that lays metadata
+/// out as a self-describing TLV trailer (see [`api_v2ext`]) instead of the
+/// single meta-flag byte used by [`APIV2`]. It is not a distinct wire API
+/// version: callers opt into it explicitly when they want forward-compatible
+/// per-value metadata.
+#[derive(Default, Clone, Copy)]
+pub struct APIV2Ext;
 
 #[macro_export]
 macro_rules! match_template_api_version {
@@ -143,12 +169,93 @@ pub enum KeyMode {
 /// | 0x12 0x34 0x56 | 0x00 0x00 0x00 0x00 0x00 0x00 0xff 0xff | 0x01 (0b00000001) |
 /// --------------------------------------------------------------------------------
 /// ```
+///
+/// The next bit (bit 1) of the meta flag indicates that the value is stored
+/// indirectly: the slot that would otherwise hold the user value instead holds
+/// a fixed-size [`DIGEST_LEN`]-byte content hash of the real payload, which is
+/// kept in a separate blob store outside of this crate's concern. This mirrors
+/// how large trie nodes are replaced by their hash to keep encoded entries
+/// small.
+///
+/// ```text
+/// --------------------------------------------------------------------------------
+/// | Digest (32B)                                                | Meta flags     |
+/// --------------------------------------------------------------------------------
+/// | ...                                                         | 0x02 (0b00000010) |
+/// --------------------------------------------------------------------------------
+/// ```
+///
+/// ### ApiVersion::V2, via `APIV2Ext`
+///
+/// Instead of a single meta-flag byte, metadata is appended as a sequence of
+/// `(tag, len, bytes)` records after the user value; see [`api_v2ext`] for the
+/// exact layout. `checksum` and `write_time` below are only ever populated by
+/// this encoding.
+///
+/// ### ApiVersion::V2, compressed
+///
+/// Bit 2 of the meta flag indicates that the user value was compressed with
+/// [`api_v2::encode_raw_value_with_codec`]. A one-byte codec id then sits
+/// between the optional expire ts and the meta flags:
+///
+/// ```text
+/// ---------------------------------------------------------------------
+/// | Compressed user value | Expire Ts (optional) | Codec id | Meta flags |
+/// ---------------------------------------------------------------------
+/// ```
+///
+/// `codec` below records which codec was used, or [`CODEC_NONE`] for
+/// uncompressed values.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RawValue<T: AsRef<[u8]>> {
-    /// The user value.
-    pub user_value: T,
+    /// The user value, either stored inline or as an indirect content hash.
+    pub user_value: UserValue<T>,
     /// The unix timestamp in seconds indicating the point of time that this key will be deleted.
     pub expire_ts: Option<u64>,
+    /// The CRC32 checksum of the user value. Only populated by `APIV2Ext`.
+    pub checksum: Option<u32>,
+    /// The unix timestamp in seconds at which the value was written. Only populated by
+    /// `APIV2Ext`.
+    pub write_time: Option<u64>,
+    /// The codec the user value was compressed with, or [`CODEC_NONE`]. Only populated by
+    /// `APIV2`.
+    pub codec: u8,
+}
+
+/// No compression; the user value is stored as-is.
+pub const CODEC_NONE: u8 = 0;
+/// The user value is compressed with LZ4 (frame format).
+pub const CODEC_LZ4: u8 = 1;
+/// The user value is compressed with Zstandard.
+pub const CODEC_ZSTD: u8 = 2;
+
+/// The length, in bytes, of the content hash used by [`UserValue::Indirect`].
+pub const DIGEST_LEN: usize = 32;
+
+/// The payload carried by a [`RawValue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserValue<T: AsRef<[u8]>> {
+    /// The real user value, stored inline.
+    Inline(T),
+    /// A content hash standing in for a value stored out-of-line in a
+    /// separate blob store.
+    Indirect([u8; DIGEST_LEN]),
+}
+
+impl<T: AsRef<[u8]>> UserValue<T> {
+    /// Whether this value is stored indirectly as a content hash.
+    pub fn is_indirect(&self) -> bool {
+        matches!(self, UserValue::Indirect(_))
+    }
+}
+
+impl<T: AsRef<[u8]>> AsRef<[u8]> for UserValue<T> {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            UserValue::Inline(v) => v.as_ref(),
+            UserValue::Indirect(digest) => digest,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -318,6 +425,168 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_indirect_value() {
+        // (digest, expire_ts, encoded_bytes)
+        let digest = [7u8; DIGEST_LEN];
+        let cases = vec![
+            (digest, None, {
+                let mut bytes = digest.to_vec();
+                bytes.push(0b0000_0010);
+                bytes
+            }),
+            (digest, Some(2), {
+                let mut bytes = digest.to_vec();
+                bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 2]);
+                bytes.push(0b0000_0011);
+                bytes
+            }),
+        ];
+        for (digest, expire_ts, encoded_bytes) in cases {
+            let raw_value = RawValue {
+                user_value: UserValue::Indirect(digest),
+                expire_ts,
+                checksum: None,
+                write_time: None,
+                codec: CODEC_NONE,
+            };
+            assert_eq!(APIV2::encode_raw_value(raw_value), encoded_bytes);
+            assert_eq!(
+                APIV2::decode_raw_value(&encoded_bytes).unwrap(),
+                raw_value
+            );
+
+            let raw_value = RawValue {
+                user_value: UserValue::Indirect(digest),
+                expire_ts,
+                checksum: None,
+                write_time: None,
+                codec: CODEC_NONE,
+            };
+            assert_eq!(
+                APIV2::encode_raw_value_owned(RawValue {
+                    user_value: UserValue::Indirect(digest),
+                    expire_ts,
+                    checksum: None,
+                    write_time: None,
+                    codec: CODEC_NONE,
+                }),
+                encoded_bytes
+            );
+            assert_eq!(
+                APIV2::decode_raw_value_owned(encoded_bytes).unwrap(),
+                raw_value
+            );
+        }
+    }
+
+    #[test]
+    fn test_v2ext_value_round_trip() {
+        let cases: Vec<(&[u8], Option<u64>, Option<u32>, Option<u64>)> = vec![
+            (b"", None, None, None),
+            (b"a", Some(2), None, None),
+            (b"long user value", Some(2), Some(0xdead_beef), Some(3)),
+        ];
+        for (user_value, expire_ts, checksum, write_time) in cases {
+            let raw_value = RawValue {
+                user_value: UserValue::Inline(user_value),
+                expire_ts,
+                checksum,
+                write_time,
+                codec: CODEC_NONE,
+            };
+            let encoded = APIV2Ext::encode_raw_value(raw_value);
+            assert_eq!(APIV2Ext::decode_raw_value(&encoded).unwrap(), raw_value);
+
+            let owned = RawValue {
+                user_value: UserValue::Inline(user_value.to_vec()),
+                expire_ts,
+                checksum,
+                write_time,
+                codec: CODEC_NONE,
+            };
+            let encoded_owned = APIV2Ext::encode_raw_value_owned(owned.clone());
+            assert_eq!(encoded_owned, encoded);
+            assert_eq!(
+                APIV2Ext::decode_raw_value_owned(encoded_owned).unwrap(),
+                owned
+            );
+        }
+    }
+
+    #[test]
+    fn test_v2ext_skips_unknown_tags() {
+        let mut encoded = b"abc".to_vec();
+        // An unknown tag 0x05 with 2 bytes of payload, spliced in before the
+        // known expire_ts tag.
+        encoded.extend_from_slice(&[0x05, 2, 0xaa, 0xbb]);
+        encoded.extend_from_slice(&[0x01, 8, 0, 0, 0, 0, 0, 0, 0, 2]);
+        let trailer_len = (encoded.len() - 3) as u32;
+        encoded.extend_from_slice(&trailer_len.to_be_bytes());
+        encoded.push(1);
+
+        let decoded = APIV2Ext::decode_raw_value(&encoded).unwrap();
+        assert_eq!(decoded.user_value.as_ref(), b"abc");
+        assert_eq!(decoded.expire_ts, Some(2));
+    }
+
+    #[test]
+    fn test_compressed_value_round_trip() {
+        // (user_value, expire_ts, codec)
+        let cases: Vec<(&[u8], Option<u64>, u8)> = vec![
+            (b"", None, CODEC_LZ4),
+            (b"", None, CODEC_ZSTD),
+            (b"hello world, hello world, hello world", None, CODEC_LZ4),
+            (b"hello world, hello world, hello world", Some(2), CODEC_ZSTD),
+        ];
+        for (user_value, expire_ts, codec) in cases {
+            let raw_value = RawValue {
+                user_value: UserValue::Inline(user_value),
+                expire_ts,
+                checksum: None,
+                write_time: None,
+                codec: CODEC_NONE,
+            };
+            let encoded = encode_raw_value_with_codec(raw_value, codec).unwrap();
+
+            // The borrowed decode can't inflate in place: it surfaces the
+            // still-compressed payload and the codec that was used.
+            let decoded = APIV2::decode_raw_value(&encoded).unwrap();
+            assert_eq!(decoded.expire_ts, expire_ts);
+            assert_eq!(decoded.codec, codec);
+
+            // Only the owned decode transparently inflates back to plaintext.
+            let decoded_owned = APIV2::decode_raw_value_owned(encoded.clone()).unwrap();
+            assert_eq!(decoded_owned.user_value.as_ref(), user_value);
+            assert_eq!(decoded_owned.expire_ts, expire_ts);
+
+            let encoded_owned = encode_raw_value_owned_with_codec(
+                RawValue {
+                    user_value: UserValue::Inline(user_value.to_vec()),
+                    expire_ts,
+                    checksum: None,
+                    write_time: None,
+                    codec: CODEC_NONE,
+                },
+                codec,
+            )
+            .unwrap();
+            assert_eq!(encoded_owned, encoded);
+        }
+    }
+
+    #[test]
+    fn test_compressed_value_undefined_codec_errs() {
+        let raw_value = RawValue {
+            user_value: UserValue::Inline(&b"hello"[..]),
+            expire_ts: None,
+            checksum: None,
+            write_time: None,
+            codec: CODEC_NONE,
+        };
+        assert!(encode_raw_value_with_codec(raw_value, 3).is_err());
+    }
+
     #[test]
     fn test_value_decode_err() {
         let cases = vec![
@@ -330,9 +599,15 @@ mod tests {
             // expire_ts is expected.
             (vec![1], ApiVersion::V2),
             (vec![1, 2, 3, 4, 5, 6, 7, 1], ApiVersion::V2),
-            // Undefined flag.
+            // The indirect bit requires a DIGEST_LEN-byte digest to precede it.
             (vec![2], ApiVersion::V2),
             (vec![1, 2, 3, 4, 5, 6, 7, 8, 2], ApiVersion::V2),
+            // The compressed bit requires a codec id byte to precede it.
+            (vec![4], ApiVersion::V2),
+            // Undefined codec id.
+            (vec![b'a', 3, 4], ApiVersion::V2),
+            // Undefined flag.
+            (vec![8], ApiVersion::V2),
         ];
 
         for (bytes, api_version) in cases {
@@ -359,15 +634,21 @@ mod tests {
             match api_version {
                 ApiVersion::API => {
                     let raw_value = RawValue {
-                        user_value,
+                        user_value: UserValue::Inline(user_value),
                         expire_ts,
+                        checksum: None,
+                        write_time: None,
+                        codec: CODEC_NONE,
                     };
                     assert_eq!(&API::encode_raw_value(raw_value), encoded_bytes);
                     assert_eq!(API::decode_raw_value(encoded_bytes).unwrap(), raw_value);
 
                     let raw_value = RawValue {
-                        user_value: user_value.to_vec(),
+                        user_value: UserValue::Inline(user_value.to_vec()),
                         expire_ts,
+                        checksum: None,
+                        write_time: None,
+                        codec: CODEC_NONE,
                     };
                     assert_eq!(
                         API::encode_raw_value_owned(raw_value.clone()),
@@ -0,0 +1,190 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Online, pairwise migration between the raw key/value encodings used by
+//! different `ApiVersion`s.
+//!
+//! This lets a background job stream existing data encoded under one
+//! `ApiVersion` (e.g. V1 or V1ttl) and re-encode it under another (e.g. V2)
+//! one key/value pair at a time, so a cluster can be migrated in place while
+//! both the old and new encodings are still being read and written.
+
+use engine_traits::Result;
+use kvproto::kvrpcpb::ApiVersion;
+use tikv_util::box_err;
+use txn_types::Key;
+
+use crate::{api_v2, match_template_api_version, APIVersion};
+
+/// Re-encodes a raw value produced under `from` into the equivalent bytes
+/// under `to`, preserving `expire_ts` across the two encodings when `to`
+/// carries one; `ApiVersion::V1` has no expire_ts, so transcoding into it
+/// drops the expire_ts.
+///
+/// Fails if `bytes` holds an indirect (content-hash) value and `to` has no
+/// indirection bit to carry it -- only `ApiVersion::V2` does. Writing the
+/// digest out as if it were the literal user value would silently corrupt
+/// the data, so the caller must resolve the digest through the blob store
+/// before transcoding it into such a format.
+pub fn transcode_raw_value(bytes: Vec<u8>, from: ApiVersion, to: ApiVersion) -> Result<Vec<u8>> {
+    let raw_value = match_template_api_version!(
+        FromAPI,
+        match from {
+            ApiVersion::FromAPI => FromAPI::decode_raw_value_owned(bytes)?,
+        }
+    );
+    if raw_value.user_value.is_indirect() && to != ApiVersion::V2 {
+        return Err(box_err!(
+            "cannot transcode an indirect value into {:?}, which has no indirection bit; \
+             resolve the digest through the blob store first",
+            to
+        ));
+    }
+    let encoded = match_template_api_version!(
+        ToAPI,
+        match to {
+            ApiVersion::ToAPI => ToAPI::encode_raw_value_owned(raw_value),
+        }
+    );
+    Ok(encoded)
+}
+
+/// Re-encodes a raw key produced under `from` into the equivalent
+/// `txn_types::Key` under `to`, adding or stripping the `r` raw-key-mode
+/// prefix and the memcomparable encoding + inverted-timestamp suffix that V2
+/// keys carry, as needed.
+///
+/// Panics if `encoded_key` is not validly encoded under `from` -- this
+/// mirrors `APIVersion::decode_raw_key`, which treats a corrupt on-disk key
+/// as a bug rather than a recoverable error, so a malformed memcomparable
+/// padding never turns into silently wrong output.
+pub fn transcode_raw_key(
+    encoded_key: Key,
+    from: ApiVersion,
+    to: ApiVersion,
+    with_ts: bool,
+) -> Result<Key> {
+    let (mut user_key, ts) = match_template_api_version!(
+        FromAPI,
+        match from {
+            ApiVersion::FromAPI => FromAPI::decode_raw_key_owned(encoded_key, with_ts)?,
+        }
+    );
+
+    match (from == ApiVersion::V2, to == ApiVersion::V2) {
+        (true, false) => {
+            if user_key.first() != Some(&api_v2::RAW_KEY_PREFIX) {
+                return Err(box_err!(
+                    "cannot transcode a non-raw-mode key out of API V2"
+                ));
+            }
+            user_key.remove(0);
+        }
+        (false, true) => user_key.insert(0, api_v2::RAW_KEY_PREFIX),
+        _ => {}
+    }
+
+    let encoded = match_template_api_version!(
+        ToAPI,
+        match to {
+            ApiVersion::ToAPI => ToAPI::encode_raw_key_owned(user_key, ts),
+        }
+    );
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RawValue, UserValue, APIV1, APIV1TTL, APIV2};
+
+    #[test]
+    fn test_transcode_raw_value_v1_v2_round_trip() {
+        for user_value in [&b""[..], &b"foo"[..]] {
+            let v1 = APIV1::encode_raw_value(RawValue {
+                user_value: UserValue::Inline(user_value),
+                expire_ts: None,
+                checksum: None,
+                write_time: None,
+                codec: crate::CODEC_NONE,
+            });
+            let v2 = transcode_raw_value(v1, ApiVersion::V1, ApiVersion::V2).unwrap();
+            let decoded = APIV2::decode_raw_value(&v2).unwrap();
+            assert_eq!(decoded.user_value.as_ref(), user_value);
+            assert_eq!(decoded.expire_ts, None);
+
+            let back = transcode_raw_value(v2, ApiVersion::V2, ApiVersion::V1).unwrap();
+            assert_eq!(back, user_value);
+        }
+    }
+
+    #[test]
+    fn test_transcode_raw_value_v1ttl_v2_round_trip_preserves_expire_ts() {
+        for (user_value, expire_ts) in [(&b""[..], 2u64), (&b"foo"[..], 42u64)] {
+            let v1ttl = APIV1TTL::encode_raw_value(RawValue {
+                user_value: UserValue::Inline(user_value),
+                expire_ts: Some(expire_ts),
+                checksum: None,
+                write_time: None,
+                codec: crate::CODEC_NONE,
+            });
+            let v2 = transcode_raw_value(v1ttl, ApiVersion::V1ttl, ApiVersion::V2).unwrap();
+            let decoded = APIV2::decode_raw_value(&v2).unwrap();
+            assert_eq!(decoded.user_value.as_ref(), user_value);
+            assert_eq!(decoded.expire_ts, Some(expire_ts));
+
+            let back = transcode_raw_value(v2, ApiVersion::V2, ApiVersion::V1ttl).unwrap();
+            let decoded = APIV1TTL::decode_raw_value(&back).unwrap();
+            assert_eq!(decoded.user_value.as_ref(), user_value);
+            assert_eq!(decoded.expire_ts, Some(expire_ts));
+        }
+    }
+
+    #[test]
+    fn test_transcode_raw_value_indirect_into_non_v2_fails_cleanly() {
+        // An indirect value has no representation in V1/V1ttl, which have no
+        // indirection bit; transcoding it there must fail rather than
+        // silently writing the digest out as if it were the real value.
+        let v2 = APIV2::encode_raw_value_owned(RawValue {
+            user_value: UserValue::Indirect([7u8; crate::DIGEST_LEN]),
+            expire_ts: None,
+            checksum: None,
+            write_time: None,
+            codec: crate::CODEC_NONE,
+        });
+        assert!(transcode_raw_value(v2.clone(), ApiVersion::V2, ApiVersion::V1).is_err());
+        assert!(transcode_raw_value(v2, ApiVersion::V2, ApiVersion::V1ttl).is_err());
+    }
+
+    #[test]
+    fn test_transcode_raw_value_undefined_v2_flag_fails_cleanly() {
+        // Flag 0b1000 is undefined, so decoding it while transcoding out of V2
+        // must return an error rather than silently misinterpreting the bytes.
+        let malformed = vec![b'a', 0b0000_1000];
+        assert!(transcode_raw_value(malformed, ApiVersion::V2, ApiVersion::V1).is_err());
+    }
+
+    #[test]
+    fn test_transcode_raw_key_round_trip() {
+        let user_key = b"hello";
+        let v1_key = APIV1::encode_raw_key_owned(user_key.to_vec(), None);
+        let v2_key =
+            transcode_raw_key(v1_key.clone(), ApiVersion::V1, ApiVersion::V2, false).unwrap();
+        let (decoded, ts) = APIV2::decode_raw_key(&v2_key, false).unwrap();
+        // V2 raw keys carry the `r` raw-key-mode prefix that V1 keys don't have.
+        assert_eq!(decoded, [&[api_v2::RAW_KEY_PREFIX][..], user_key].concat());
+        assert_eq!(ts, None);
+
+        let back = transcode_raw_key(v2_key, ApiVersion::V2, ApiVersion::V1, false).unwrap();
+        assert_eq!(back, v1_key);
+    }
+
+    #[test]
+    fn test_transcode_raw_key_malformed_padding_panics() {
+        // Not a validly memcomparable-encoded V2 key.
+        let bad_v2_key = Key::from_encoded(vec![b'r', 2, 3, 4, 5, 6, 7, 8]);
+        let res = panic_hook::recover_safe(|| {
+            let _ = transcode_raw_key(bad_v2_key, ApiVersion::V2, ApiVersion::V1, false);
+        });
+        assert!(res.is_err());
+    }
+}